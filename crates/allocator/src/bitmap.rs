@@ -9,16 +9,23 @@ use crate::{AllocError, AllocResult, BaseAllocator, PageAllocator};
 // Support max 1M * 4096 = 4GB memory.
 type BitAllocUsed = bitmap_allocator::BitAlloc1M;
 
+/// The largest alignment supported by [`BitmapPageAllocator::alloc_pages`].
+const MAX_ALIGN_1GB: usize = 0x4000_0000;
+
 /// A page-granularity memory allocator based on the [bitmap_allocator].
 ///
 /// It internally uses a bitmap, each bit indicates whether a page has been
 /// allocated.
 ///
-/// The `PAGE_SIZE` must be a power of two.
+/// The `PAGE_SIZE` must be a power of two. Allocations can be aligned to any
+/// power of two from `PAGE_SIZE` up to [`max_align`](Self::max_align), which
+/// is 1 GB unless the region is close to the capacity of the bitmap.
 ///
 /// [bitmap_allocator]: https://github.com/rcore-os/bitmap-allocator
 pub struct BitmapPageAllocator<const PAGE_SIZE: usize> {
     base: usize,
+    start_idx: usize,
+    max_align: usize,
     total_pages: usize,
     used_pages: usize,
     inner: BitAllocUsed,
@@ -29,11 +36,92 @@ impl<const PAGE_SIZE: usize> BitmapPageAllocator<PAGE_SIZE> {
     pub const fn new() -> Self {
         Self {
             base: 0,
+            start_idx: 0,
+            max_align: 0,
             total_pages: 0,
             used_pages: 0,
             inner: BitAllocUsed::DEFAULT,
         }
     }
+
+    /// Returns the largest alignment supported by
+    /// [`alloc_pages`](PageAllocator::alloc_pages).
+    ///
+    /// The bitmap is indexed from the start of the region aligned down to this
+    /// value, so that an aligned page index is also an aligned address. It is
+    /// the largest power of two up to 1 GB for which the pages below the
+    /// region and the region itself still fit in the bitmap.
+    pub fn max_align(&self) -> usize {
+        self.max_align
+    }
+
+    /// Returns the length (in pages) of the longest run of contiguous free
+    /// pages.
+    pub fn largest_free_run(&self) -> usize {
+        let mut largest = 0;
+        let mut run = 0;
+        for idx in self.start_idx..self.start_idx + self.total_pages {
+            if self.inner.test(idx) {
+                run += 1;
+                largest = largest.max(run);
+            } else {
+                run = 0;
+            }
+        }
+        largest
+    }
+
+    /// Returns the base address of the first run of at least `min_pages`
+    /// contiguous free pages, or `None` if there is no such run.
+    ///
+    /// A run of zero pages is meaningless, so `min_pages == 0` always returns
+    /// `None`.
+    pub fn first_free_run(&self, min_pages: usize) -> Option<usize> {
+        if min_pages == 0 {
+            return None;
+        }
+        let mut start = 0;
+        let mut run = 0;
+        for idx in self.start_idx..self.start_idx + self.total_pages {
+            if self.inner.test(idx) {
+                if run == 0 {
+                    start = idx;
+                }
+                run += 1;
+                if run >= min_pages {
+                    return Some(start * PAGE_SIZE + self.base);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        None
+    }
+
+    /// Frees `num_pages` contiguous pages starting at `pos`.
+    ///
+    /// The range can be any part of a previous allocation. It is validated
+    /// before anything is freed: returns [`AllocError::InvalidParam`] if it is
+    /// unaligned, out of the managed memory, or if any page in it is already
+    /// free (double free).
+    pub fn free_pages(&mut self, pos: usize, num_pages: usize) -> AllocResult {
+        if pos % PAGE_SIZE != 0 || pos < self.base {
+            return Err(AllocError::InvalidParam);
+        }
+        let start = (pos - self.base) / PAGE_SIZE;
+        let end = start
+            .checked_add(num_pages)
+            .ok_or(AllocError::InvalidParam)?;
+        if start < self.start_idx || end > self.start_idx + self.total_pages {
+            return Err(AllocError::InvalidParam);
+        }
+        if (start..end).any(|idx| self.inner.test(idx)) {
+            return Err(AllocError::InvalidParam);
+        }
+        self.inner.insert(start..end);
+        self.used_pages -= num_pages;
+        Ok(())
+    }
 }
 
 impl<const PAGE_SIZE: usize> BaseAllocator for BitmapPageAllocator<PAGE_SIZE> {
@@ -41,9 +129,21 @@ impl<const PAGE_SIZE: usize> BaseAllocator for BitmapPageAllocator<PAGE_SIZE> {
         assert!(PAGE_SIZE.is_power_of_two());
         let end = super::align_down(start + size, PAGE_SIZE);
         let start = super::align_up(start, PAGE_SIZE);
-        self.base = start;
         self.total_pages = (end - start) / PAGE_SIZE;
-        self.inner.insert(0..self.total_pages);
+        assert!(
+            self.total_pages <= BitAllocUsed::CAP,
+            "memory region [{:#x}, {:#x}) is too large for the page bitmap",
+            start,
+            end
+        );
+        self.max_align = MAX_ALIGN_1GB;
+        while start % self.max_align / PAGE_SIZE + self.total_pages > BitAllocUsed::CAP {
+            self.max_align /= 2;
+        }
+        self.base = super::align_down(start, self.max_align);
+        self.start_idx = (start - self.base) / PAGE_SIZE;
+        self.inner
+            .insert(self.start_idx..self.start_idx + self.total_pages);
     }
 
     fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
@@ -55,7 +155,7 @@ impl<const PAGE_SIZE: usize> PageAllocator for BitmapPageAllocator<PAGE_SIZE> {
     const PAGE_SIZE: usize = PAGE_SIZE;
 
     fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        if align_pow2 % PAGE_SIZE != 0 {
+        if align_pow2 % PAGE_SIZE != 0 || align_pow2 > self.max_align {
             return Err(AllocError::InvalidParam);
         }
         let align_pow2 = align_pow2 / PAGE_SIZE;
@@ -64,8 +164,10 @@ impl<const PAGE_SIZE: usize> PageAllocator for BitmapPageAllocator<PAGE_SIZE> {
         }
         let align_log2 = align_pow2.trailing_zeros() as usize;
         match num_pages.cmp(&1) {
-            core::cmp::Ordering::Equal => self.inner.alloc().map(|idx| idx * PAGE_SIZE + self.base),
-            core::cmp::Ordering::Greater => self
+            core::cmp::Ordering::Equal if align_log2 == 0 => {
+                self.inner.alloc().map(|idx| idx * PAGE_SIZE + self.base)
+            }
+            core::cmp::Ordering::Equal | core::cmp::Ordering::Greater => self
                 .inner
                 .alloc_contiguous(num_pages, align_log2)
                 .map(|idx| idx * PAGE_SIZE + self.base),
@@ -76,9 +178,9 @@ impl<const PAGE_SIZE: usize> PageAllocator for BitmapPageAllocator<PAGE_SIZE> {
     }
 
     fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
-        // TODO: not decrease `used_pages` if deallocation failed
-        self.used_pages -= num_pages;
-        self.inner.dealloc((pos - self.base) / PAGE_SIZE)
+        if let Err(err) = self.free_pages(pos, num_pages) {
+            panic!("failed to deallocate {num_pages} pages at {pos:#x}: {err:?}");
+        }
     }
 
     fn total_pages(&self) -> usize {
@@ -7,6 +7,12 @@
 //! - [`PageAllocator`]: Page-granularity memory allocator. (e.g.,
 //!   [`BitmapPageAllocator`])
 //! - [`IdAllocator`]: Used to allocate unique IDs.
+//!
+//! With the `slab_cache` feature, a `SlabCache` serves objects of a single size
+//! from slabs taken from any [`PageAllocator`].
+//!
+//! With the `global_alloc` feature, any [`ByteAllocator`] can be wrapped in a
+//! `GlobalByteAllocator` and registered as the `#[global_allocator]`.
 
 #![no_std]
 #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
@@ -26,6 +32,11 @@ mod slab;
 #[cfg(feature = "slab")]
 pub use slab::SlabByteAllocator;
 
+#[cfg(feature = "slab_cache")]
+mod slab_cache;
+#[cfg(feature = "slab_cache")]
+pub use slab_cache::SlabCache;
+
 #[cfg(feature = "tlsf")]
 mod tlsf;
 #[cfg(feature = "tlsf")]
@@ -64,9 +75,44 @@ pub trait ByteAllocator: BaseAllocator {
     /// Allocate memory with the given size (in bytes) and alignment.
     fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>>;
 
+    /// Allocate zero-initialized memory with the given size and alignment.
+    ///
+    /// The default implementation calls [`alloc`](Self::alloc) and then fills
+    /// the memory with zeros. Allocators that know a region is already zeroed
+    /// may override it to skip the fill.
+    fn alloc_zeroed(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let pos = self.alloc(layout)?;
+        unsafe { core::ptr::write_bytes(pos.as_ptr(), 0, layout.size()) };
+        Ok(pos)
+    }
+
     /// Deallocate memory at the given position, size, and alignment.
     fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout);
 
+    /// Reallocate memory at the given position to a new size and alignment.
+    ///
+    /// The contents are preserved up to the smaller of the old and new sizes.
+    /// The default implementation allocates a new block, copies the contents,
+    /// and deallocates the old block. On failure, the old block is left
+    /// untouched.
+    fn realloc(
+        &mut self,
+        pos: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> AllocResult<NonNull<u8>> {
+        let new_pos = self.alloc(new_layout)?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                pos.as_ptr(),
+                new_pos.as_ptr(),
+                old_layout.size().min(new_layout.size()),
+            );
+        }
+        self.dealloc(pos, old_layout);
+        Ok(new_pos)
+    }
+
     /// Returns total memory size in bytes.
     fn total_bytes(&self) -> usize;
 
@@ -178,3 +224,83 @@ mod allocator_api {
 
 #[cfg(feature = "allocator_api")]
 pub use allocator_api::AllocatorRc;
+
+#[cfg(feature = "global_alloc")]
+mod global_alloc {
+    use super::{AllocResult, ByteAllocator};
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::ptr::{null_mut, NonNull};
+    use spinlock::SpinNoIrq;
+
+    /// A byte-allocator wrapped in [`SpinNoIrq`] that implements
+    /// [`core::alloc::GlobalAlloc`], so it can be registered with the
+    /// `#[global_allocator]` attribute.
+    ///
+    /// Allocation failures are reported as null pointers.
+    pub struct GlobalByteAllocator<A: ByteAllocator>(SpinNoIrq<A>);
+
+    impl<A: ByteAllocator> GlobalByteAllocator<A> {
+        /// Creates a new global allocator wrapping the given byte-allocator.
+        ///
+        /// [`GlobalByteAllocator::init`] must be called before any allocation.
+        pub const fn new(inner: A) -> Self {
+            Self(SpinNoIrq::new(inner))
+        }
+
+        /// Initialize the inner allocator with a free memory region.
+        pub fn init(&self, start: usize, size: usize) {
+            self.0.lock().init(start, size)
+        }
+
+        /// Add a free memory region to the inner allocator.
+        pub fn add_memory(&self, start: usize, size: usize) -> AllocResult {
+            self.0.lock().add_memory(start, size)
+        }
+
+        /// Returns total memory size in bytes.
+        pub fn total_bytes(&self) -> usize {
+            self.0.lock().total_bytes()
+        }
+
+        /// Returns allocated memory size in bytes.
+        pub fn used_bytes(&self) -> usize {
+            self.0.lock().used_bytes()
+        }
+
+        /// Returns available memory size in bytes.
+        pub fn available_bytes(&self) -> usize {
+            self.0.lock().available_bytes()
+        }
+    }
+
+    unsafe impl<A: ByteAllocator> GlobalAlloc for GlobalByteAllocator<A> {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.0
+                .lock()
+                .alloc(layout)
+                .map_or(null_mut(), |ptr| ptr.as_ptr())
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            self.0
+                .lock()
+                .alloc_zeroed(layout)
+                .map_or(null_mut(), |ptr| ptr.as_ptr())
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+            self.0
+                .lock()
+                .realloc(NonNull::new_unchecked(ptr), layout, new_layout)
+                .map_or(null_mut(), |ptr| ptr.as_ptr())
+        }
+    }
+}
+
+#[cfg(feature = "global_alloc")]
+pub use global_alloc::GlobalByteAllocator;
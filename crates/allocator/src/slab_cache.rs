@@ -0,0 +1,202 @@
+//! Slab cache for objects of a single size, backed by a page allocator.
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+
+use crate::{AllocError, AllocResult, PageAllocator};
+
+/// Slabs are grown (in power-of-two pages) until they hold at least this many
+/// objects.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// The largest slab, in bytes.
+///
+/// Slabs are aligned to their own size, and page allocators do not support
+/// alignments beyond 1 GB.
+const MAX_SLAB_SIZE: usize = 0x4000_0000;
+
+/// The header at the start of every slab.
+struct SlabHeader {
+    /// The next slab of the cache.
+    next: Option<NonNull<SlabHeader>>,
+    /// The free objects of this slab.
+    free: Option<NonNull<FreeObject>>,
+    /// The number of objects handed out from this slab.
+    in_use: usize,
+}
+
+/// A free object, linked into the free list of its slab.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// A cache of equally sized objects, carved from slabs of pages that are taken
+/// from a [`PageAllocator`].
+///
+/// Each slab is a run of [`slab_pages`](Self::slab_pages) pages aligned to its
+/// own size, starting with a small header followed by the objects. The slab of
+/// an object is found by aligning its address down, so freeing is O(1). One
+/// empty slab is kept, so that allocating and freeing a single object in a
+/// loop does not take and give back pages every time. Any further slab is
+/// given back to the page allocator as soon as none of its objects is in use.
+pub struct SlabCache<P: PageAllocator> {
+    palloc: P,
+    obj_size: usize,
+    first_offset: usize,
+    objects_per_slab: usize,
+    slab_pages: usize,
+    slabs: Option<NonNull<SlabHeader>>,
+    empty_slabs: usize,
+    objects_in_use: usize,
+}
+
+unsafe impl<P: PageAllocator + Send> Send for SlabCache<P> {}
+
+impl<P: PageAllocator> SlabCache<P> {
+    /// Creates an empty cache for objects of the given layout, taking slabs
+    /// from `palloc`.
+    ///
+    /// Returns [`AllocError::InvalidParam`] if a slab holding enough objects of
+    /// this layout would be larger than 1 GB.
+    pub fn new(palloc: P, layout: Layout) -> AllocResult<Self> {
+        let align = layout.align().max(align_of::<FreeObject>());
+        let obj_size = super::align_up(layout.size().max(size_of::<FreeObject>()), align);
+        let first_offset = super::align_up(size_of::<SlabHeader>(), align);
+        let objects_in = |pages: usize| -> AllocResult<usize> {
+            let slab_size = pages
+                .checked_mul(P::PAGE_SIZE)
+                .filter(|&size| size <= MAX_SLAB_SIZE)
+                .ok_or(AllocError::InvalidParam)?;
+            Ok(slab_size.saturating_sub(first_offset) / obj_size)
+        };
+        let mut slab_pages = 1;
+        while objects_in(slab_pages)? < MIN_OBJECTS_PER_SLAB {
+            slab_pages *= 2;
+        }
+        Ok(Self {
+            palloc,
+            obj_size,
+            first_offset,
+            objects_per_slab: objects_in(slab_pages)?,
+            slab_pages,
+            slabs: None,
+            empty_slabs: 0,
+            objects_in_use: 0,
+        })
+    }
+
+    /// Returns the size of each object slot in bytes, including padding.
+    pub fn object_size(&self) -> usize {
+        self.obj_size
+    }
+
+    /// Returns the number of objects in each slab.
+    pub fn objects_per_slab(&self) -> usize {
+        self.objects_per_slab
+    }
+
+    /// Returns the number of pages in each slab.
+    pub fn slab_pages(&self) -> usize {
+        self.slab_pages
+    }
+
+    /// Returns the number of allocated objects.
+    pub fn objects_in_use(&self) -> usize {
+        self.objects_in_use
+    }
+
+    /// Returns the underlying page allocator.
+    pub fn page_allocator(&self) -> &P {
+        &self.palloc
+    }
+
+    /// Allocates an object, taking a new slab from the page allocator if all
+    /// slabs are full.
+    pub fn alloc_obj(&mut self) -> AllocResult<NonNull<u8>> {
+        let mut slab = match self.find_free_slab() {
+            Some(slab) => slab,
+            None => self.new_slab()?,
+        };
+        let header = unsafe { slab.as_mut() };
+        if header.in_use == 0 {
+            self.empty_slabs -= 1;
+        }
+        let obj = header.free.unwrap();
+        header.free = unsafe { obj.as_ref().next };
+        header.in_use += 1;
+        self.objects_in_use += 1;
+        Ok(obj.cast())
+    }
+
+    /// Frees an object returned by [`alloc_obj`](Self::alloc_obj).
+    ///
+    /// If the slab holding it no longer has objects in use, it is kept as the
+    /// empty slab of the cache, or given back to the page allocator if there
+    /// already is one.
+    pub fn free_obj(&mut self, ptr: NonNull<u8>) {
+        let base = super::align_down(ptr.as_ptr() as usize, self.slab_pages * P::PAGE_SIZE);
+        let mut slab = unsafe { NonNull::new_unchecked(base as *mut SlabHeader) };
+        let header = unsafe { slab.as_mut() };
+        let obj = ptr.cast::<FreeObject>();
+        unsafe { obj.as_ptr().write(FreeObject { next: header.free }) };
+        header.free = Some(obj);
+        header.in_use -= 1;
+        self.objects_in_use -= 1;
+        if header.in_use == 0 {
+            if self.empty_slabs > 0 {
+                self.release_slab(slab);
+            } else {
+                self.empty_slabs += 1;
+            }
+        }
+    }
+
+    fn find_free_slab(&self) -> Option<NonNull<SlabHeader>> {
+        let mut slab = self.slabs;
+        while let Some(s) = slab {
+            let header = unsafe { s.as_ref() };
+            if header.free.is_some() {
+                return Some(s);
+            }
+            slab = header.next;
+        }
+        None
+    }
+
+    fn new_slab(&mut self) -> AllocResult<NonNull<SlabHeader>> {
+        let slab_size = self.slab_pages * P::PAGE_SIZE;
+        let base = self.palloc.alloc_pages(self.slab_pages, slab_size)?;
+        let mut free = None;
+        for i in (0..self.objects_per_slab).rev() {
+            let obj = (base + self.first_offset + i * self.obj_size) as *mut FreeObject;
+            unsafe { obj.write(FreeObject { next: free }) };
+            free = NonNull::new(obj);
+        }
+        let slab = unsafe { NonNull::new_unchecked(base as *mut SlabHeader) };
+        unsafe {
+            slab.as_ptr().write(SlabHeader {
+                next: self.slabs,
+                free,
+                in_use: 0,
+            })
+        };
+        self.slabs = Some(slab);
+        self.empty_slabs += 1;
+        Ok(slab)
+    }
+
+    fn release_slab(&mut self, slab: NonNull<SlabHeader>) {
+        let next = unsafe { slab.as_ref().next };
+        let mut link = &mut self.slabs;
+        while let Some(mut s) = *link {
+            if s == slab {
+                *link = next;
+                break;
+            }
+            link = unsafe { &mut s.as_mut().next };
+        }
+        self.palloc
+            .dealloc_pages(slab.as_ptr() as usize, self.slab_pages);
+    }
+}
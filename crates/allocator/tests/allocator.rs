@@ -1,14 +1,19 @@
 #![feature(btreemap_alloc)]
 #![feature(allocator_api)]
 
-use std::alloc::{Allocator, Layout};
+use std::alloc::{Allocator, GlobalAlloc, Layout};
 use std::collections::BTreeMap;
 use std::io::Write;
 
-use allocator::{AllocatorRc, BuddyByteAllocator, SlabByteAllocator, TlsfByteAllocator};
+use allocator::{
+    AllocError, AllocatorRc, BaseAllocator, BitmapPageAllocator, BuddyByteAllocator, ByteAllocator,
+    GlobalByteAllocator, PageAllocator, SlabByteAllocator, SlabCache, TlsfByteAllocator,
+};
 use rand::{prelude::SliceRandom, Rng};
 
 const POOL_SIZE: usize = 1024 * 1024 * 128;
+const PAGE_SIZE: usize = 0x1000;
+const PAGE_POOL_BASE: usize = 0x8000_0000;
 
 fn test_vec(n: usize, alloc: &(impl Allocator + Clone)) {
     let mut v = Vec::with_capacity_in(n, alloc.clone());
@@ -84,6 +89,48 @@ pub fn test_alignment(n: usize, alloc: &(impl Allocator + Clone)) {
     }
 }
 
+fn test_realloc(mut alloc: impl ByteAllocator, pool: &mut [u8]) {
+    alloc.init(pool.as_mut_ptr() as usize, pool.len());
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = alloc.alloc(layout).unwrap();
+    unsafe { core::ptr::write_bytes(ptr.as_ptr(), 0xa5, layout.size()) };
+
+    // grow: the old contents must be carried over
+    let new_layout = Layout::from_size_align(8192, 64).unwrap();
+    let ptr = alloc.realloc(ptr, layout, new_layout).unwrap();
+    assert_eq!(ptr.as_ptr() as usize % new_layout.align(), 0);
+    let data = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+    assert!(data.iter().all(|&b| b == 0xa5));
+    unsafe { core::ptr::write_bytes(ptr.as_ptr(), 0x5a, new_layout.size()) };
+
+    // shrink: only the leading bytes are kept
+    let layout = new_layout;
+    let new_layout = Layout::from_size_align(16, 8).unwrap();
+    let ptr = alloc.realloc(ptr, layout, new_layout).unwrap();
+    assert_eq!(ptr.as_ptr() as usize % new_layout.align(), 0);
+    let data = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), new_layout.size()) };
+    assert!(data.iter().all(|&b| b == 0x5a));
+
+    alloc.dealloc(ptr, new_layout);
+}
+
+fn test_alloc_zeroed(mut alloc: impl ByteAllocator, pool: &mut [u8]) {
+    alloc.init(pool.as_mut_ptr() as usize, pool.len());
+
+    for size in [8, 100, 4096, 10000] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = alloc.alloc(layout).unwrap();
+        unsafe { core::ptr::write_bytes(ptr.as_ptr(), 0xff, size) };
+        alloc.dealloc(ptr, layout);
+
+        let ptr = alloc.alloc_zeroed(layout).unwrap();
+        let data = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), size) };
+        assert!(data.iter().all(|&b| b == 0));
+        alloc.dealloc(ptr, layout);
+    }
+}
+
 fn run_test(f: impl FnOnce(&mut [u8])) {
     let layout = Layout::from_size_align(POOL_SIZE, 4096).unwrap();
     let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
@@ -141,3 +188,302 @@ fn tlsf_alloc() {
         test_btree_map(50_000, &alloc);
     })
 }
+
+#[test]
+fn realloc() {
+    run_test(|pool| {
+        test_realloc(BuddyByteAllocator::new(), pool);
+        test_realloc(SlabByteAllocator::new(), pool);
+        test_realloc(TlsfByteAllocator::new(), pool);
+    })
+}
+
+#[test]
+fn alloc_zeroed() {
+    run_test(|pool| {
+        test_alloc_zeroed(BuddyByteAllocator::new(), pool);
+        test_alloc_zeroed(SlabByteAllocator::new(), pool);
+        test_alloc_zeroed(TlsfByteAllocator::new(), pool);
+    })
+}
+
+#[test]
+fn global_alloc() {
+    run_test(|pool| {
+        let alloc = GlobalByteAllocator::new(TlsfByteAllocator::new());
+        alloc.init(pool.as_mut_ptr() as usize, pool.len());
+
+        let layout = Layout::array::<u64>(1024).unwrap();
+        let ptr = unsafe { alloc.alloc(layout) } as *mut u64;
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % layout.align(), 0);
+        for i in 0..1024 {
+            unsafe { ptr.add(i).write(i as u64) };
+        }
+        assert_eq!(alloc.used_bytes(), layout.size());
+
+        let new_size = layout.size() * 4;
+        let ptr = unsafe { alloc.realloc(ptr as *mut u8, layout, new_size) } as *mut u64;
+        assert!(!ptr.is_null());
+        for i in 0..1024 {
+            assert_eq!(unsafe { ptr.add(i).read() }, i as u64);
+        }
+
+        let layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+        unsafe { alloc.dealloc(ptr as *mut u8, layout) };
+        assert_eq!(alloc.used_bytes(), 0);
+
+        // failures are reported as null instead of panicking
+        let layout = Layout::from_size_align(POOL_SIZE * 2, 8).unwrap();
+        assert!(unsafe { alloc.alloc(layout) }.is_null());
+    })
+}
+
+#[test]
+fn bitmap_free_runs() {
+    let page = |idx: usize| PAGE_POOL_BASE + idx * PAGE_SIZE;
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    alloc.init(PAGE_POOL_BASE, 64 * PAGE_SIZE);
+    assert_eq!(alloc.largest_free_run(), 64);
+    assert_eq!(alloc.first_free_run(64), Some(page(0)));
+    assert_eq!(alloc.first_free_run(65), None);
+    assert_eq!(alloc.first_free_run(0), None);
+
+    for _ in 0..64 {
+        alloc.alloc_pages(1, PAGE_SIZE).unwrap();
+    }
+    assert_eq!(alloc.largest_free_run(), 0);
+    assert_eq!(alloc.first_free_run(1), None);
+    assert_eq!(alloc.first_free_run(0), None);
+
+    // free runs of 3, 7 and 5 pages
+    for idx in (2..5).chain(10..17).chain(30..35) {
+        alloc.dealloc_pages(page(idx), 1);
+    }
+    assert_eq!(alloc.largest_free_run(), 7);
+    assert_eq!(alloc.first_free_run(1), Some(page(2)));
+    assert_eq!(alloc.first_free_run(3), Some(page(2)));
+    assert_eq!(alloc.first_free_run(4), Some(page(10)));
+    assert_eq!(alloc.first_free_run(7), Some(page(10)));
+    assert_eq!(alloc.first_free_run(8), None);
+    assert_eq!(alloc.first_free_run(0), None);
+}
+
+#[test]
+fn bitmap_alloc_align() {
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    // start one page past an aligned boundary so that alignment matters
+    alloc.init(PAGE_POOL_BASE + PAGE_SIZE, 16 * 1024 * 1024);
+    assert_eq!(
+        alloc.alloc_pages(1, PAGE_SIZE).unwrap(),
+        PAGE_POOL_BASE + PAGE_SIZE
+    );
+
+    for align in [0x1000, 0x4000, 0x20_0000] {
+        for num_pages in [1, 3] {
+            let addr = alloc.alloc_pages(num_pages, align).unwrap();
+            assert_eq!(addr % align, 0);
+            assert!(addr > PAGE_POOL_BASE);
+        }
+    }
+
+    assert!(matches!(
+        alloc.alloc_pages(1, 3 * PAGE_SIZE),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        alloc.alloc_pages(1, PAGE_SIZE / 2),
+        Err(AllocError::InvalidParam)
+    ));
+}
+
+#[test]
+fn bitmap_free_pages() {
+    let page = |idx: usize| PAGE_POOL_BASE + idx * PAGE_SIZE;
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    alloc.init(PAGE_POOL_BASE, 16 * PAGE_SIZE);
+
+    // deallocating a run frees every page of it
+    let addr = alloc.alloc_pages(4, PAGE_SIZE).unwrap();
+    alloc.dealloc_pages(addr, 4);
+    assert_eq!(alloc.available_pages(), 16);
+
+    // free the middle of a larger allocation
+    assert_eq!(alloc.alloc_pages(8, PAGE_SIZE).unwrap(), page(0));
+    alloc.free_pages(page(2), 3).unwrap();
+    assert_eq!(alloc.used_pages(), 5);
+    assert_eq!(alloc.first_free_run(3), Some(page(2)));
+
+    // overlaps already freed pages
+    assert!(matches!(
+        alloc.free_pages(page(4), 2),
+        Err(AllocError::InvalidParam)
+    ));
+    // out of range or unaligned
+    assert!(matches!(
+        alloc.free_pages(page(15), 2),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        alloc.free_pages(PAGE_POOL_BASE - PAGE_SIZE, 1),
+        Err(AllocError::InvalidParam)
+    ));
+    assert!(matches!(
+        alloc.free_pages(page(0) + 1, 1),
+        Err(AllocError::InvalidParam)
+    ));
+    assert_eq!(alloc.used_pages(), 5);
+
+    alloc.free_pages(page(0), 2).unwrap();
+    alloc.free_pages(page(5), 3).unwrap();
+    assert_eq!(alloc.used_pages(), 0);
+    assert_eq!(alloc.largest_free_run(), 16);
+}
+
+#[test]
+fn bitmap_page_stats() {
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    alloc.init(PAGE_POOL_BASE, 32 * PAGE_SIZE);
+    let check = |alloc: &BitmapPageAllocator<PAGE_SIZE>, used: usize| {
+        assert_eq!(alloc.total_pages(), 32);
+        assert_eq!(alloc.used_pages(), used);
+        assert_eq!(alloc.available_pages(), 32 - used);
+    };
+    check(&alloc, 0);
+
+    let a = alloc.alloc_pages(4, PAGE_SIZE).unwrap();
+    let b = alloc.alloc_pages(8, 8 * PAGE_SIZE).unwrap();
+    let c = alloc.alloc_pages(1, PAGE_SIZE).unwrap();
+    check(&alloc, 13);
+
+    // failed operations leave the counts alone
+    assert!(alloc.alloc_pages(32, PAGE_SIZE).is_err());
+    assert!(alloc.free_pages(b + 8 * PAGE_SIZE, 32).is_err());
+    check(&alloc, 13);
+
+    alloc.dealloc_pages(b, 8);
+    check(&alloc, 5);
+    alloc.free_pages(a, 2).unwrap();
+    check(&alloc, 3);
+    alloc.free_pages(a + 2 * PAGE_SIZE, 2).unwrap();
+    alloc.dealloc_pages(c, 1);
+    check(&alloc, 0);
+    assert_eq!(alloc.largest_free_run(), alloc.available_pages());
+}
+
+#[test]
+fn bitmap_init_max_align() {
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    alloc.init(PAGE_POOL_BASE, 16 * PAGE_SIZE);
+    assert_eq!(alloc.max_align(), 0x4000_0000);
+
+    // 4 GB starting 768 MB past a 1 GB boundary only fits when indexed from
+    // the last 256 MB boundary
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    alloc.init(PAGE_POOL_BASE + 0x3000_0000, 0x1_0000_0000);
+    assert_eq!(alloc.max_align(), 0x1000_0000);
+    assert_eq!(alloc.available_pages(), 0x10_0000);
+    assert!(matches!(
+        alloc.alloc_pages(1, 0x2000_0000),
+        Err(AllocError::InvalidParam)
+    ));
+    let addr = alloc.alloc_pages(2, 0x1000_0000).unwrap();
+    assert_eq!(addr, PAGE_POOL_BASE + 0x3000_0000);
+    let addr = alloc.alloc_pages(1, 0x1000_0000).unwrap();
+    assert_eq!(addr, PAGE_POOL_BASE + 0x4000_0000);
+}
+
+#[test]
+#[should_panic(expected = "too large for the page bitmap")]
+fn bitmap_init_too_large() {
+    let mut alloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    alloc.init(PAGE_POOL_BASE, 0x1_0000_0000 + PAGE_SIZE);
+}
+
+fn new_slab_cache(pool: &mut [u8], layout: Layout) -> SlabCache<BitmapPageAllocator<PAGE_SIZE>> {
+    let mut palloc = BitmapPageAllocator::<PAGE_SIZE>::new();
+    palloc.init(pool.as_mut_ptr() as usize, pool.len());
+    SlabCache::new(palloc, layout).unwrap()
+}
+
+#[test]
+fn slab_cache_grow() {
+    run_test(|pool| {
+        let layout = Layout::from_size_align(48, 16).unwrap();
+        let mut cache = new_slab_cache(pool, layout);
+        let per_slab = cache.objects_per_slab();
+        let slab_pages = cache.slab_pages();
+
+        let mut objs = Vec::new();
+        for i in 0..per_slab {
+            let obj = cache.alloc_obj().unwrap();
+            unsafe { core::ptr::write_bytes(obj.as_ptr(), i as u8, layout.size()) };
+            objs.push(obj);
+        }
+        assert_eq!(cache.page_allocator().used_pages(), slab_pages);
+
+        // the first slab is exhausted, so the next object needs a new slab
+        objs.push(cache.alloc_obj().unwrap());
+        assert_eq!(cache.page_allocator().used_pages(), 2 * slab_pages);
+        assert_eq!(cache.objects_in_use(), per_slab + 1);
+
+        for (i, obj) in objs[..per_slab].iter().enumerate() {
+            assert_eq!(obj.as_ptr() as usize % layout.align(), 0);
+            let data = unsafe { core::slice::from_raw_parts(obj.as_ptr(), layout.size()) };
+            assert!(data.iter().all(|&b| b == i as u8));
+        }
+        let mut addrs: Vec<_> = objs.iter().map(|obj| obj.as_ptr() as usize).collect();
+        addrs.sort();
+        assert!(addrs.windows(2).all(|w| w[1] - w[0] >= layout.size()));
+    })
+}
+
+#[test]
+fn slab_cache_release() {
+    run_test(|pool| {
+        let mut cache = new_slab_cache(pool, Layout::new::<[u64; 4]>());
+        let per_slab = cache.objects_per_slab();
+        let slab_pages = cache.slab_pages();
+
+        let objs: Vec<_> = (0..3 * per_slab)
+            .map(|_| cache.alloc_obj().unwrap())
+            .collect();
+        assert_eq!(cache.page_allocator().used_pages(), 3 * slab_pages);
+
+        // the first slab to become empty is kept
+        for &obj in &objs[..per_slab] {
+            cache.free_obj(obj);
+        }
+        assert_eq!(cache.page_allocator().used_pages(), 3 * slab_pages);
+        assert_eq!(cache.objects_in_use(), 2 * per_slab);
+
+        // further empty slabs are given back
+        for &obj in &objs[per_slab..] {
+            cache.free_obj(obj);
+        }
+        assert_eq!(cache.page_allocator().used_pages(), slab_pages);
+        assert_eq!(cache.objects_in_use(), 0);
+
+        // allocating and freeing in a loop reuses the empty slab
+        for _ in 0..4 {
+            let obj = cache.alloc_obj().unwrap();
+            assert_eq!(cache.page_allocator().used_pages(), slab_pages);
+            cache.free_obj(obj);
+            assert_eq!(cache.page_allocator().used_pages(), slab_pages);
+        }
+    })
+}
+
+#[test]
+fn slab_cache_oversized() {
+    let new_cache = |size| {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        SlabCache::new(BitmapPageAllocator::<PAGE_SIZE>::new(), layout)
+    };
+    // 8 objects of 32 MB still fit in a 512 MB slab
+    assert_eq!(new_cache(0x200_0000).unwrap().slab_pages(), 0x2_0000);
+    // slabs would have to be larger than 1 GB
+    for size in [0x1000_0000, 1 << 40, isize::MAX as usize - 7] {
+        assert!(matches!(new_cache(size), Err(AllocError::InvalidParam)));
+    }
+}